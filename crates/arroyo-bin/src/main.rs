@@ -5,16 +5,23 @@ use arroyo_server_common::{log_event, start_admin_server};
 use arroyo_types::{ports, DatabaseConfig};
 use arroyo_worker::WorkerServer;
 use clap::{Parser, Subcommand};
-use deadpool_postgres::{ManagerConfig, Pool, RecyclingMethod};
+use deadpool_postgres::{ManagerConfig, Pool, PoolConfig, RecyclingMethod, Timeouts};
+use native_tls::{Certificate, Identity};
+use postgres_native_tls::MakeTlsConnector;
 use serde_json::json;
+use std::env;
 use std::process::exit;
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::time::timeout;
-use tokio_postgres::{Client, Connection, NoTls};
-use tracing::{debug, error, info};
+use tokio_postgres::config::SslMode;
+use tokio_postgres::{Client, Connection};
+use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
+mod pg_notify;
+
 #[derive(Parser)]
 #[command(version, about)]
 struct Cli {
@@ -47,6 +54,10 @@ enum Commands {
         /// If set, waits for the specified number of seconds until Postgres is ready before running migrations
         #[arg(long)]
         wait: Option<u32>,
+
+        /// Name of the table refinery uses to track applied migrations
+        #[arg(long, default_value = "refinery_schema_history")]
+        migration_table: String,
     },
 }
 
@@ -89,8 +100,11 @@ async fn main() {
         Commands::Worker { .. } => {
             start_worker().await;
         }
-        Commands::Migrate { wait } => {
-            if let Err(e) = migrate(*wait).await {
+        Commands::Migrate {
+            wait,
+            migration_table,
+        } => {
+            if let Err(e) = migrate(*wait, migration_table).await {
                 error!("{}", e);
                 exit(1);
             }
@@ -101,42 +115,384 @@ async fn main() {
     };
 }
 
+/// Postgres TLS negotiation mode, mirroring libpq's `sslmode`.
+///
+/// This (along with the rest of [`TlsConfig`]) belongs conceptually on
+/// `arroyo_types::DatabaseConfig`, but that crate isn't part of this
+/// checkout, so it's configured independently here and loaded alongside it.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+enum TlsMode {
+    /// Never use TLS.
+    Disable,
+    /// Use TLS if the server supports it, but don't fail if it doesn't. Like
+    /// `Require`, does not verify the server's certificate or hostname --
+    /// `verify-full` is the only mode that does.
+    Prefer,
+    /// Require TLS, but don't verify the server's certificate.
+    Require,
+    /// Require TLS and verify the server's certificate and hostname.
+    VerifyFull,
+}
+
+impl TlsMode {
+    fn from_env() -> Self {
+        match env::var("DATABASE_TLS_MODE")
+            .unwrap_or_else(|_| "disable".to_string())
+            .to_lowercase()
+            .as_str()
+        {
+            "disable" => TlsMode::Disable,
+            "prefer" => TlsMode::Prefer,
+            "require" => TlsMode::Require,
+            "verify-full" => TlsMode::VerifyFull,
+            other => {
+                error!(
+                    "Invalid DATABASE_TLS_MODE '{}'; expected one of disable, prefer, require, verify-full",
+                    other
+                );
+                exit(1);
+            }
+        }
+    }
+
+    fn as_ssl_mode(&self) -> SslMode {
+        match self {
+            TlsMode::Disable => SslMode::Disable,
+            TlsMode::Prefer => SslMode::Prefer,
+            TlsMode::Require | TlsMode::VerifyFull => SslMode::Require,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct TlsConfig {
+    mode: TlsMode,
+    ca_cert_file: Option<String>,
+    client_cert_file: Option<String>,
+    client_key_file: Option<String>,
+}
+
+impl TlsConfig {
+    fn from_env() -> Self {
+        Self {
+            mode: TlsMode::from_env(),
+            ca_cert_file: env::var("DATABASE_TLS_CA_CERT_FILE").ok(),
+            client_cert_file: env::var("DATABASE_TLS_CLIENT_CERT_FILE").ok(),
+            client_key_file: env::var("DATABASE_TLS_CLIENT_KEY_FILE").ok(),
+        }
+    }
+}
+
+/// Builds a [`MakeTlsConnector`] honoring the given [`TlsConfig`]. This is used
+/// even when TLS is disabled -- in that case `ssl_mode(SslMode::Disable)` on the
+/// connection config ensures it's never invoked.
+fn build_tls_connector(tls: &TlsConfig) -> anyhow::Result<MakeTlsConnector> {
+    let mut builder = native_tls::TlsConnector::builder();
+
+    if let Some(ca_cert_file) = &tls.ca_cert_file {
+        let pem = std::fs::read(ca_cert_file).map_err(|e| {
+            anyhow!(
+                "Unable to read DATABASE_TLS_CA_CERT_FILE {}: {}",
+                ca_cert_file,
+                e
+            )
+        })?;
+        builder.add_root_certificate(
+            Certificate::from_pem(&pem)
+                .map_err(|e| anyhow!("Invalid CA certificate in {}: {}", ca_cert_file, e))?,
+        );
+    }
+
+    if let (Some(cert_file), Some(key_file)) = (&tls.client_cert_file, &tls.client_key_file) {
+        let cert = std::fs::read(cert_file).map_err(|e| {
+            anyhow!(
+                "Unable to read DATABASE_TLS_CLIENT_CERT_FILE {}: {}",
+                cert_file,
+                e
+            )
+        })?;
+        let key = std::fs::read(key_file).map_err(|e| {
+            anyhow!(
+                "Unable to read DATABASE_TLS_CLIENT_KEY_FILE {}: {}",
+                key_file,
+                e
+            )
+        })?;
+        builder.identity(
+            Identity::from_pkcs8(&cert, &key)
+                .map_err(|e| anyhow!("Invalid client cert/key pair: {}", e))?,
+        );
+    }
+
+    if matches!(tls.mode, TlsMode::Prefer | TlsMode::Require) {
+        // Encrypt the connection without verifying the server's certificate or hostname.
+        // Only `verify-full` validates; `prefer`'s whole point is opportunistic
+        // encryption against e.g. a self-signed/internal CA, so it must be just
+        // as lenient as `require` here -- `SslMode::Prefer` only falls back to
+        // plaintext when the server declines TLS outright, not on a cert failure.
+        builder.danger_accept_invalid_certs(true);
+        builder.danger_accept_invalid_hostnames(true);
+    }
+
+    let connector = builder
+        .build()
+        .map_err(|e| anyhow!("Unable to build TLS connector: {}", e))?;
+
+    Ok(MakeTlsConnector::new(connector))
+}
+
+/// Returns true if `message` looks like a transient connection failure (refused,
+/// reset, DNS resolution failure, timeout) that's worth retrying, as opposed to a
+/// fatal configuration error (bad credentials, missing database).
+fn is_transient_db_error(message: &str) -> bool {
+    const TRANSIENT_PATTERNS: [&str; 9] = [
+        "connection refused",
+        "connection reset",
+        "could not translate host name",
+        "failed to lookup address information",
+        "timed out",
+        "timeout",
+        "broken pipe",
+        "the database system is starting up",
+        "server closed the connection unexpectedly",
+    ];
+    let message = message.to_lowercase();
+    TRANSIENT_PATTERNS.iter().any(|p| message.contains(p))
+}
+
+#[cfg(test)]
+mod db_retry_tests {
+    use super::*;
+
+    #[test]
+    fn is_transient_db_error_detects_startup_and_network_blips() {
+        assert!(is_transient_db_error(
+            "FATAL: the database system is starting up"
+        ));
+        assert!(is_transient_db_error(
+            "error connecting to server: Connection refused (os error 111)"
+        ));
+        assert!(is_transient_db_error(
+            "server closed the connection unexpectedly"
+        ));
+    }
+
+    #[test]
+    fn is_transient_db_error_treats_auth_and_missing_db_as_fatal() {
+        assert!(!is_transient_db_error(
+            "password authentication failed for user \"arroyo\""
+        ));
+        assert!(!is_transient_db_error("database \"arroyo\" does not exist"));
+    }
+
+    #[test]
+    fn backoff_delay_is_monotonic_and_capped() {
+        let mut previous = Duration::from_millis(0);
+        for attempt in 0..10 {
+            let delay = backoff_delay(attempt);
+            assert!(delay >= previous);
+            assert!(delay <= Duration::from_secs(10));
+            previous = delay;
+        }
+        assert_eq!(backoff_delay(10), Duration::from_secs(10));
+    }
+}
+
+/// Bounds for retrying the initial connection to the database on startup.
+struct DbConnectRetryConfig {
+    max_attempts: u32,
+    max_elapsed: Duration,
+}
+
+impl DbConnectRetryConfig {
+    fn from_env() -> Self {
+        Self {
+            max_attempts: env::var("DATABASE_CONNECT_RETRY_ATTEMPTS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(10),
+            max_elapsed: Duration::from_secs(
+                env::var("DATABASE_CONNECT_RETRY_SECONDS")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(60),
+            ),
+        }
+    }
+}
+
+/// Exponential backoff, starting at 500ms and capping at 10s.
+fn backoff_delay(attempt: u32) -> Duration {
+    let millis = 500u64.saturating_mul(1u64 << attempt.min(5));
+    Duration::from_millis(millis.min(10_000))
+}
+
+fn env_duration_secs(name: &str) -> Option<Duration> {
+    env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .map(Duration::from_secs)
+}
+
+/// Tuning knobs for the deadpool connection pool. Deadpool has no built-in
+/// concept of pre-warmed idle connections -- it creates them lazily on demand
+/// -- so `min_idle` is enforced here by eagerly acquiring and releasing that
+/// many connections right after the pool is built, rather than by a deadpool
+/// setting.
+struct PoolTuningConfig {
+    max_size: usize,
+    min_idle: usize,
+    wait_timeout: Option<Duration>,
+    create_timeout: Option<Duration>,
+    recycle_timeout: Option<Duration>,
+    health_check: bool,
+}
+
+impl PoolTuningConfig {
+    fn from_env() -> Self {
+        Self {
+            max_size: env::var("DATABASE_POOL_MAX_SIZE")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(10),
+            min_idle: env::var("DATABASE_POOL_MIN_IDLE")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0),
+            wait_timeout: env_duration_secs("DATABASE_POOL_WAIT_TIMEOUT_SECONDS"),
+            create_timeout: env_duration_secs("DATABASE_POOL_CREATE_TIMEOUT_SECONDS"),
+            recycle_timeout: env_duration_secs("DATABASE_POOL_RECYCLE_TIMEOUT_SECONDS"),
+            health_check: env::var("DATABASE_POOL_HEALTH_CHECK")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+        }
+    }
+}
+
+/// Eagerly acquires and releases `min_idle` connections so the pool starts out
+/// with that many already established instead of creating them lazily on the
+/// first requests to need them.
+///
+/// `min_idle` is clamped to `max_size`: warming more connections than the pool
+/// can ever hold would deadlock here, since none of them are released back to
+/// the pool until every `pool.get()` call in the batch resolves.
+async fn warm_pool(pool: &Pool, min_idle: usize, max_size: usize) {
+    let min_idle = if min_idle > max_size {
+        warn!(
+            "DATABASE_POOL_MIN_IDLE ({}) exceeds DATABASE_POOL_MAX_SIZE ({}); clamping",
+            min_idle, max_size
+        );
+        max_size
+    } else {
+        min_idle
+    };
+
+    if min_idle == 0 {
+        return;
+    }
+
+    let conns = futures::future::join_all((0..min_idle).map(|_| pool.get())).await;
+    let failures = conns.iter().filter(|c| c.is_err()).count();
+    if failures > 0 {
+        warn!(
+            "Only warmed {}/{} idle database connections",
+            min_idle - failures,
+            min_idle
+        );
+    }
+    // Connections are returned to the pool as soon as `conns` is dropped.
+}
+
 async fn db_pool() -> Pool {
     let config = DatabaseConfig::load();
+    let tls = TlsConfig::from_env();
+    let retry = DbConnectRetryConfig::from_env();
+    let tuning = PoolTuningConfig::from_env();
     let mut cfg = deadpool_postgres::Config::new();
     cfg.dbname = Some(config.name.clone());
     cfg.host = Some(config.host.clone());
     cfg.port = Some(config.port);
     cfg.user = Some(config.user.clone());
     cfg.password = Some(config.password.clone());
+    cfg.ssl_mode = Some(tls.mode.as_ssl_mode());
     cfg.manager = Some(ManagerConfig {
-        recycling_method: RecyclingMethod::Fast,
+        recycling_method: if tuning.health_check {
+            // Runs a cheap validation query before a recycled connection is handed back out.
+            RecyclingMethod::Verified
+        } else {
+            RecyclingMethod::Fast
+        },
+    });
+    cfg.pool = Some(PoolConfig {
+        max_size: tuning.max_size,
+        timeouts: Timeouts {
+            wait: tuning.wait_timeout,
+            create: tuning.create_timeout,
+            recycle: tuning.recycle_timeout,
+        },
+        ..Default::default()
+    });
+    info!(
+        "Database pool configured: max_size={} min_idle={} health_check={} wait_timeout={:?} create_timeout={:?} recycle_timeout={:?}",
+        tuning.max_size,
+        tuning.min_idle,
+        tuning.health_check,
+        tuning.wait_timeout,
+        tuning.create_timeout,
+        tuning.recycle_timeout
+    );
+    let connector = build_tls_connector(&tls).unwrap_or_else(|e| {
+        error!("Unable to configure database TLS: {:?}", e);
+        exit(1);
     });
     let pool = cfg
-        .create_pool(Some(deadpool_postgres::Runtime::Tokio1), NoTls)
+        .create_pool(Some(deadpool_postgres::Runtime::Tokio1), connector)
         .unwrap_or_else(|e| {
             error!("Unable to connect to database {}: {:?}", config, e);
             exit(1);
         });
 
-    match pool
-        .get()
-        .await
-        .unwrap_or_else(|e| {
-            error!("Unable to create database connection for {} {}", config, e);
-            exit(1);
-        })
-        .query_one("select id from cluster_info", &[])
-        .await
-    {
-        Ok(row) => {
-            let uuid: Uuid = row.get(0);
-            arroyo_server_common::set_cluster_id(&uuid.to_string());
+    let started = std::time::Instant::now();
+    let mut attempt = 0u32;
+    loop {
+        attempt += 1;
+        let result: anyhow::Result<Uuid> = async {
+            let client = pool.get().await?;
+            let row = client.query_one("select id from cluster_info", &[]).await?;
+            Ok(row.get(0))
         }
-        Err(e) => {
-            panic!("Failed to get cluster info {:?}", e);
+        .await;
+
+        match result {
+            Ok(uuid) => {
+                arroyo_server_common::set_cluster_id(&uuid.to_string());
+                break;
+            }
+            Err(e) => {
+                let message = e.to_string();
+                if !is_transient_db_error(&message) {
+                    error!("Failed to get cluster info from {}: {:?}", config, e);
+                    exit(1);
+                }
+
+                if attempt >= retry.max_attempts || started.elapsed() >= retry.max_elapsed {
+                    error!(
+                        "Giving up connecting to database {} after {} attempts: {:?}",
+                        config, attempt, e
+                    );
+                    exit(1);
+                }
+
+                warn!(
+                    "Transient error connecting to database {} (attempt {}/{}): {:?}; retrying",
+                    config, attempt, retry.max_attempts, e
+                );
+                tokio::time::sleep(backoff_delay(attempt)).await;
+            }
         }
-    };
+    }
+
+    warm_pool(&pool, tuning.min_idle, tuning.max_size).await;
 
     pool
 }
@@ -153,15 +509,24 @@ async fn connect(
     Connection<impl AsyncRead + AsyncWrite + Unpin, impl AsyncRead + AsyncWrite + Unpin>,
 )> {
     let config = DatabaseConfig::load();
+    let tls = TlsConfig::from_env();
+    let connector = build_tls_connector(&tls)?;
+
+    // Migrations run DDL, so allow operators to grant this role `CREATE` while
+    // keeping the runtime application role (used by `db_pool()`) restricted to DML.
+    let user = env::var("DATABASE_MIGRATION_USER").unwrap_or_else(|_| config.user.clone());
+    let password =
+        env::var("DATABASE_MIGRATION_PASSWORD").unwrap_or_else(|_| config.password.clone());
 
     loop {
         match tokio_postgres::config::Config::new()
             .host(&config.host)
             .port(config.port)
-            .user(&config.user)
-            .password(&config.password)
+            .user(&user)
+            .password(&password)
             .dbname(&config.name)
-            .connect(NoTls)
+            .ssl_mode(tls.mode.as_ssl_mode())
+            .connect(connector.clone())
             .await
         {
             Ok(r) => {
@@ -180,7 +545,7 @@ async fn connect(
     }
 }
 
-async fn migrate(wait: Option<u32>) -> anyhow::Result<()> {
+async fn migrate(wait: Option<u32>, migration_table: &str) -> anyhow::Result<()> {
     let _guard = arroyo_server_common::init_logging("migrate");
 
     let (mut client, connection) = if let Some(wait) = wait {
@@ -200,16 +565,16 @@ async fn migrate(wait: Option<u32>) -> anyhow::Result<()> {
 
     info!("Running migrations on database {}", DatabaseConfig::load());
 
-    let report = migrations::migrations::runner()
-        .run_async(&mut client)
-        .await
-        .map_err(|e| {
-            anyhow!(
-                "Failed to run migrations on {}: {:?}",
-                DatabaseConfig::load(),
-                e
-            )
-        })?;
+    let mut runner = migrations::migrations::runner();
+    runner.set_migration_table_name(migration_table);
+
+    let report = runner.run_async(&mut client).await.map_err(|e| {
+        anyhow!(
+            "Failed to run migrations on {}: {:?}",
+            DatabaseConfig::load(),
+            e
+        )
+    })?;
 
     for migration in report.applied_migrations() {
         info!("Applying V{} {}", migration.version(), migration.name());
@@ -223,6 +588,19 @@ async fn migrate(wait: Option<u32>) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Logs every time `channel` fires. Stands in for the controller-side work that
+/// would actually re-check job/pipeline state, until `arroyo-controller` itself
+/// subscribes to the channels it cares about.
+async fn watch_channel(
+    notify: Arc<tokio::sync::Notify>,
+    channel: &'static str,
+) -> anyhow::Result<()> {
+    loop {
+        notify.notified().await;
+        debug!("Received database notification on '{}'", channel);
+    }
+}
+
 async fn start_control_plane(service: CPService) {
     let _guard = arroyo_server_common::init_logging(service.name());
 
@@ -243,6 +621,21 @@ async fn start_control_plane(service: CPService) {
         start_admin_server(service.name(), ports::API_ADMIN),
     );
 
+    // Lets the controller react immediately to state changes written by the API
+    // server, rather than waiting on its next poll -- most useful when API and
+    // controller run as separate processes (i.e. anything other than `Cluster`).
+    // The job/pipeline state-update code paths that would issue the matching
+    // `NOTIFY` (and `arroyo_controller::ControllerServer`, which would consume
+    // these channels directly) aren't part of this checkout, so the handle is
+    // held locally rather than reaching into that crate's constructor. The
+    // spawned task below is a real subscriber in the meantime -- it reacts to
+    // notifications on `job_state_change`, demonstrating the wiring end-to-end.
+    let pg_notify = pg_notify::PgNotify::start(&["job_state_change", "pipeline_state_change"]);
+    shutdown.spawn_task(
+        "pg-notify-job-state",
+        watch_channel(pg_notify.notify_for("job_state_change"), "job_state_change"),
+    );
+
     if service == CPService::Api || service == CPService::All {
         shutdown.spawn_task("api", arroyo_api::start_server(pool.clone()));
     }
@@ -0,0 +1,121 @@
+//! Postgres LISTEN/NOTIFY based coordination.
+//!
+//! Polling alone means the controller can take up to a full poll interval to
+//! notice a job or pipeline state change written by the API server -- which
+//! matters once API and controller run as separate processes. This module
+//! keeps a long-lived connection subscribed to a fixed set of channels and
+//! wakes whichever task is waiting on that channel as soon as a `NOTIFY`
+//! arrives, while a periodic fallback sweep guarantees a dropped notification
+//! (or a connection still reconnecting) never stalls progress indefinitely.
+
+use crate::{build_tls_connector, TlsConfig};
+use arroyo_types::DatabaseConfig;
+use dashmap::DashMap;
+use futures::stream::poll_fn;
+use futures::StreamExt;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio_postgres::AsyncMessage;
+use tracing::{info, warn};
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+const POLL_FALLBACK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Hands out [`Notify`] handles keyed by Postgres `NOTIFY` channel name, waking
+/// them whenever a notification arrives.
+#[derive(Clone)]
+pub struct PgNotify {
+    channels: Arc<DashMap<String, Arc<Notify>>>,
+}
+
+impl PgNotify {
+    /// Starts listening on `channels` in the background, reconnecting and
+    /// re-`LISTEN`ing automatically if the connection is lost.
+    pub fn start(channels: &[&str]) -> Self {
+        let map: Arc<DashMap<String, Arc<Notify>>> = Arc::new(DashMap::new());
+        for channel in channels {
+            map.insert((*channel).to_string(), Arc::new(Notify::new()));
+        }
+
+        let listen_channels: Vec<String> = channels.iter().map(|s| s.to_string()).collect();
+        tokio::spawn(Self::run(map.clone(), listen_channels));
+
+        Self { channels: map }
+    }
+
+    /// Returns the handle that's notified the next time `channel` fires, or on
+    /// the periodic polling fallback.
+    pub fn notify_for(&self, channel: &str) -> Arc<Notify> {
+        self.channels
+            .entry(channel.to_string())
+            .or_insert_with(|| Arc::new(Notify::new()))
+            .clone()
+    }
+
+    async fn run(channels: Arc<DashMap<String, Arc<Notify>>>, listen_channels: Vec<String>) {
+        tokio::spawn(Self::poll_fallback(channels.clone()));
+
+        loop {
+            if let Err(e) = Self::listen_once(&channels, &listen_channels).await {
+                warn!("Lost database LISTEN connection, reconnecting: {:?}", e);
+            }
+            tokio::time::sleep(RECONNECT_DELAY).await;
+        }
+    }
+
+    /// Wakes one waiter on every channel at a fixed interval, so a notification that's
+    /// dropped (or arrives while we're reconnecting) doesn't stall a waiter
+    /// forever.
+    async fn poll_fallback(channels: Arc<DashMap<String, Arc<Notify>>>) {
+        loop {
+            tokio::time::sleep(POLL_FALLBACK_INTERVAL).await;
+            for entry in channels.iter() {
+                entry.value().notify_one();
+            }
+        }
+    }
+
+    async fn listen_once(
+        channels: &Arc<DashMap<String, Arc<Notify>>>,
+        listen_channels: &[String],
+    ) -> anyhow::Result<()> {
+        let config = DatabaseConfig::load();
+        let tls = TlsConfig::from_env();
+        let connector = build_tls_connector(&tls)?;
+
+        let (client, mut connection) = tokio_postgres::config::Config::new()
+            .host(&config.host)
+            .port(config.port)
+            .user(&config.user)
+            .password(&config.password)
+            .dbname(&config.name)
+            .ssl_mode(tls.mode.as_ssl_mode())
+            .connect(connector)
+            .await?;
+
+        for channel in listen_channels {
+            client
+                .batch_execute(&format!("LISTEN \"{}\"", channel))
+                .await?;
+        }
+
+        info!(
+            "Listening for database notifications on {:?}",
+            listen_channels
+        );
+
+        let mut messages = poll_fn(move |cx| Pin::new(&mut connection).poll_message(cx));
+
+        while let Some(message) = messages.next().await {
+            if let AsyncMessage::Notification(n) = message? {
+                if let Some(notify) = channels.get(n.channel()) {
+                    notify.notify_one();
+                }
+            }
+        }
+
+        anyhow::bail!("notification connection closed")
+    }
+}